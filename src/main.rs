@@ -1,21 +1,57 @@
 use anyhow::{anyhow, Result};
 use clap::Parser;
 use colored::Colorize;
+use futures_util::StreamExt;
 use mime::Mime;
-use reqwest::header::{HeaderMap, AUTHORIZATION};
-use reqwest::{header, Client, Response, Url};
-use std::{collections::HashMap, str::FromStr};
+use reqwest::header::{HeaderMap, HeaderName};
+use reqwest::{header, Client, Method, RequestBuilder, Response, Url};
+use serde::{Deserialize, Serialize};
+use serde_json::Map;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::PathBuf;
+use std::str::FromStr;
 use syntect::{
     easy::HighlightLines,
     highlighting::{Style, ThemeSet},
     parsing::SyntaxSet,
     util::as_24_bit_terminal_escaped,
 };
+use tokio::io::AsyncWriteExt;
 
 /// A native httpie implementation with Rust
 #[derive(Parser, Debug)]
 #[clap(version = "1.0", author = "Wang")]
 struct Opts {
+    /// 代理服务器地址，例如 http://127.0.0.1:8080 或 socks5://127.0.0.1:1080。
+    /// 不指定时交给 reqwest 按 HTTPS_PROXY/no_proxy 等环境变量自动处理
+    #[clap(long)]
+    proxy: Option<String>,
+    /// 不使用任何代理（包括环境变量中配置的代理）
+    #[clap(long)]
+    no_proxy: bool,
+    /// 最多跟随的重定向次数
+    #[clap(long, default_value = "10")]
+    max_redirects: usize,
+    /// 不跟随重定向，直接返回 3xx 响应
+    #[clap(long)]
+    no_follow: bool,
+    /// HTTP Basic 认证，格式为 user:pass
+    #[clap(long)]
+    auth: Option<String>,
+    /// HTTP Bearer Token 认证
+    #[clap(long)]
+    bearer: Option<String>,
+    /// session 名称，用同一个名称重复调用时会复用上次保存的 auth/cookie，以及
+    /// session 文件里的 header（本次请求的自定义 header 不会写回 session）
+    #[clap(long)]
+    session: Option<String>,
+    /// 以流式方式把响应体下载到文件，而不是一次性读入内存，需要配合 -o 使用
+    #[clap(long)]
+    download: bool,
+    /// 配合 --download 使用，指定下载保存的文件路径
+    #[clap(short = 'o', long)]
+    output: Option<PathBuf>,
     #[clap(subcommand)]
     subcmd: SubCommand,
 }
@@ -24,6 +60,19 @@ struct Opts {
 enum SubCommand {
     Get(Get),
     Post(Post),
+    /// feed put with an url and optional key=value pairs. We will put the data
+    /// as JSON, and retrieve the response for you
+    Put(Post),
+    /// feed patch with an url and optional key=value pairs. We will patch the
+    /// data as JSON, and retrieve the response for you
+    Patch(Post),
+    /// feed delete with an url and optional key=value pairs. We will delete
+    /// the data as JSON, and retrieve the response for you
+    Delete(Post),
+    /// feed head with an url, we will retrieve the response headers for you
+    Head(Get),
+    /// feed options with an url, we will retrieve the response headers for you
+    Options(Get),
 }
 // get 子命令
 
@@ -47,10 +96,106 @@ struct Post {
     /// HTTP 请求的 URL
     #[clap(parse(try_from_str = parse_url))]
     url: String,
-    /// HTTP 请求的 body
-    #[clap(parse(try_from_str=parse_kv_pair))]
-    body: Vec<KvPair>,
+    /// HTTP 请求的 body，`key=value` 表示字符串，`key:=value` 表示原始 JSON
+    #[clap(parse(try_from_str=parse_body_kv))]
+    body: Vec<BodyKv>,
     header: Vec<KvPair>,
+    /// 以 application/x-www-form-urlencoded 的形式发送 body，而不是 JSON
+    #[clap(long)]
+    form: bool,
+    /// 要上传的文件，格式为 field@/path/to/file，需要配合 --multipart 使用
+    #[clap(long, parse(try_from_str = parse_file_kv))]
+    file: Vec<FileKv>,
+    /// 以 multipart/form-data 的形式发送 body 和 --file 指定的文件
+    #[clap(long)]
+    multipart: bool,
+}
+
+/// 一个待上传的文件字段：`field@/path/to/file`
+#[derive(Debug, PartialEq)]
+struct FileKv {
+    k: String,
+    path: String,
+}
+
+impl FromStr for FileKv {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || anyhow!(format!("Failed to parse {}", s));
+        let mut split = s.splitn(2, '@');
+        Ok(Self {
+            k: split.next().ok_or_else(err)?.to_string(),
+            path: split.next().ok_or_else(err)?.to_string(),
+        })
+    }
+}
+
+/// 因为我们为 FileKv 实现了 FromStr，这里可以直接 s.parse() 得到 FileKv
+fn parse_file_kv(s: &str) -> Result<FileKv> {
+    s.parse()
+}
+
+/// body 中的一个键值对。`key=value` 的 value 是字符串，`key:=value` 的 value
+/// 会被当成原始 JSON 解析（数字、布尔、数组、对象……）
+#[derive(Debug, PartialEq)]
+struct BodyKv {
+    k: String,
+    v: JsonValue,
+}
+
+/// `key=value` 与 `key:=value` 解析出来的 value 类型
+#[derive(Debug, PartialEq, Clone)]
+enum JsonValue {
+    Str(String),
+    Raw(serde_json::Value),
+}
+
+impl JsonValue {
+    fn into_json(self) -> serde_json::Value {
+        match self {
+            JsonValue::Str(s) => serde_json::Value::String(s),
+            JsonValue::Raw(v) => v,
+        }
+    }
+
+    /// 用于 --form 模式，原始 JSON 值按字符串形式发送
+    fn to_form_value(&self) -> String {
+        match self {
+            JsonValue::Str(s) => s.clone(),
+            JsonValue::Raw(v) => v.to_string(),
+        }
+    }
+}
+
+/// 当我们实现 FromStr trait 后，可以用 str.parse() 方法将字符串解析成 BodyKv
+impl FromStr for BodyKv {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || anyhow!(format!("Failed to parse {}", s));
+        // `:=` 语法：value 是一段原始 JSON，例如 count:=42、active:=true
+        if let Some(idx) = s.find(":=") {
+            let k = s[..idx].to_string();
+            let raw = &s[idx + 2..];
+            let v = serde_json::from_str(raw).map_err(|_| err())?;
+            return Ok(Self {
+                k,
+                v: JsonValue::Raw(v),
+            });
+        }
+        // 普通 `key=value` 语法：value 始终是字符串
+        let mut split = s.split('=');
+        Ok(Self {
+            k: (split.next().ok_or_else(err)?).to_string(),
+            v: JsonValue::Str((split.next().ok_or_else(err)?).to_string()),
+        })
+    }
+}
+
+/// 因为我们为 BodyKv 实现了 FromStr，这里可以直接 s.parse() 得到 BodyKv
+fn parse_body_kv(s: &str) -> Result<BodyKv> {
+    s.parse()
 }
 
 /// 命令行中的 key=value or key:value 可以通过 parse_kv_pair 解析成 KvPair 结构
@@ -91,28 +236,200 @@ fn parse_url(s: &str) -> Result<String> {
     Ok(s.into())
 }
 
-/// 处理 get 子命令
-async fn get(client: Client, args: &Get) -> Result<()> {
-    let mut headers = HeaderMap::new();
-    for header in args.header.iter() {
-        //hard code header
-        if header.k.to_lowercase() == "authorization" {
-            headers.insert(AUTHORIZATION, header.v.parse()?);
+/// 保存在 --session 文件里的认证信息：--auth 用的 user:pass 或 --bearer 用的 token
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum SessionAuth {
+    Basic(String),
+    Bearer(String),
+}
+
+/// `--session <name>` 对应的持久化内容：session 文件里的 header、上次用到的认证
+/// 信息和响应设置的 cookie，会在下一次使用同一个 session 时作为默认值合并进请求。
+/// headers 只从文件读取，请求中的自定义 header 不会被写回
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Session {
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    #[serde(default)]
+    auth: Option<SessionAuth>,
+    #[serde(default)]
+    cookies: HashMap<String, String>,
+}
+
+/// session 文件保存在用户配置目录下的 rust-httpie/sessions/<name>.json
+fn session_path(name: &str) -> Result<PathBuf> {
+    let mut dir =
+        dirs::config_dir().ok_or_else(|| anyhow!("Failed to locate the config directory"))?;
+    dir.push("rust-httpie");
+    dir.push("sessions");
+    std::fs::create_dir_all(&dir)?;
+    dir.push(format!("{}.json", name));
+    Ok(dir)
+}
+
+/// 读取 --session 指定的 session，不存在时返回一个空 session
+fn load_session(name: &str) -> Result<Session> {
+    let path = session_path(name)?;
+    if !path.exists() {
+        return Ok(Session::default());
+    }
+    let content = std::fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&content)?)
+}
+
+/// 把 session 写回磁盘，下次使用同一个 --session 时会带上这次的 auth 和 cookie
+fn save_session(name: &str, session: &Session) -> Result<()> {
+    let path = session_path(name)?;
+    std::fs::write(path, serde_json::to_string_pretty(session)?)?;
+    Ok(())
+}
+
+/// 已经加载好的 --session 上下文：session 名称 + 内容
+struct SessionContext {
+    name: String,
+    session: Session,
+}
+
+impl SessionContext {
+    /// 如果命令行带了 --session，就把对应的 session 文件读出来
+    fn load(opts: &Opts) -> Result<Option<Self>> {
+        match &opts.session {
+            Some(name) => Ok(Some(Self {
+                name: name.clone(),
+                session: load_session(name)?,
+            })),
+            None => Ok(None),
         }
-        // headers.insert(header, header.v.parse()?);
     }
-    let resp = client.get(&args.url).query(query) .headers(headers).send().await?;
-    print_resp(resp).await
+}
+
+/// 处理 get 子命令
+async fn get(client: Client, args: &Get, opts: &Opts) -> Result<()> {
+    fetch(Method::GET, client, args, opts).await
 }
 
 /// 处理 post 子命令
-async fn post(client: Client, args: &Post) -> Result<()> {
-    let mut body = HashMap::new();
+async fn post(client: Client, args: &Post, opts: &Opts) -> Result<()> {
+    mutate(Method::POST, client, args, opts).await
+}
+
+/// 把子命令携带的自定义 header、--session 保存的 header/cookie/auth，以及
+/// --auth/--bearer 全局认证选项统一应用到请求上。get/post/put/patch/delete/
+/// head/options 都通过这个 helper 设置 header 和认证，这样认证和自定义
+/// header 在所有方法上行为一致。返回实际生效的认证信息，供调用方写回 session
+fn apply_auth_and_headers(
+    mut builder: RequestBuilder,
+    headers: &[KvPair],
+    opts: &Opts,
+    ctx: Option<&SessionContext>,
+) -> Result<(RequestBuilder, Option<SessionAuth>)> {
+    let mut header_map = HeaderMap::new();
+    if let Some(ctx) = ctx {
+        for (k, v) in ctx.session.headers.iter() {
+            header_map.insert(HeaderName::from_str(k)?, v.parse()?);
+        }
+        if !ctx.session.cookies.is_empty() {
+            let cookie = ctx
+                .session
+                .cookies
+                .iter()
+                .map(|(k, v)| format!("{}={}", k, v))
+                .collect::<Vec<_>>()
+                .join("; ");
+            header_map.insert(header::COOKIE, cookie.parse()?);
+        }
+    }
+    for header in headers {
+        header_map.insert(HeaderName::from_str(&header.k)?, header.v.parse()?);
+    }
+    builder = builder.headers(header_map);
+
+    let session_auth = ctx.and_then(|ctx| ctx.session.auth.clone());
+    let effective_auth = if let Some(auth) = &opts.auth {
+        Some(SessionAuth::Basic(auth.clone()))
+    } else if let Some(token) = &opts.bearer {
+        Some(SessionAuth::Bearer(token.clone()))
+    } else {
+        session_auth
+    };
+    match &effective_auth {
+        Some(SessionAuth::Basic(auth)) => {
+            let mut split = auth.splitn(2, ':');
+            let user = split.next().unwrap_or_default().to_string();
+            let password = split.next().map(|s| s.to_string());
+            builder = builder.basic_auth(user, password);
+        }
+        Some(SessionAuth::Bearer(token)) => {
+            builder = builder.bearer_auth(token);
+        }
+        None => {}
+    }
+    Ok((builder, effective_auth))
+}
+
+/// 处理不携带 body 的子命令（get/head/options），它们共用同一个请求构造逻辑
+async fn fetch(method: Method, client: Client, args: &Get, opts: &Opts) -> Result<()> {
+    let ctx = SessionContext::load(opts)?;
+    let query: Vec<(&str, &str)> = args
+        .query
+        .iter()
+        .map(|pair| (pair.k.as_str(), pair.v.as_str()))
+        .collect();
+    let builder = client.request(method, &args.url).query(&query);
+    let (builder, auth) = apply_auth_and_headers(builder, &args.header, opts, ctx.as_ref())?;
+    let resp = builder.send().await?;
+    print_resp(resp, ctx.map(|ctx| (ctx, auth)), opts).await
+}
+
+/// 处理携带 body 的子命令（post/put/patch/delete），它们共用同一个请求构造逻辑。
+/// 默认以 JSON 发送 body，加上 `--form` 则以 x-www-form-urlencoded 发送，
+/// 加上 `--multipart` 则发送 multipart/form-data（可携带 --file 指定的文件）
+async fn mutate(method: Method, client: Client, args: &Post, opts: &Opts) -> Result<()> {
+    if args.multipart {
+        return multipart(method, client, args, opts).await;
+    }
+    let ctx = SessionContext::load(opts)?;
+    let builder = client.request(method, &args.url);
+    let (builder, auth) = apply_auth_and_headers(builder, &args.header, opts, ctx.as_ref())?;
+    let builder = if args.form {
+        let params: Vec<(String, String)> = args
+            .body
+            .iter()
+            .map(|pair| (pair.k.clone(), pair.v.to_form_value()))
+            .collect();
+        builder.form(&params)
+    } else {
+        let mut body = Map::new();
+        for pair in args.body.iter() {
+            body.insert(pair.k.clone(), pair.v.clone().into_json());
+        }
+        builder.json(&body)
+    };
+    let resp = builder.send().await?;
+    print_resp(resp, ctx.map(|ctx| (ctx, auth)), opts).await
+}
+
+/// 处理 `--multipart`：--file 指定的文件作为文件字段，body 中的 key=value
+/// 作为普通文本字段，一起发送 multipart/form-data 请求
+async fn multipart(method: Method, client: Client, args: &Post, opts: &Opts) -> Result<()> {
+    let mut form = reqwest::multipart::Form::new();
+    for kv in args.file.iter() {
+        let file = tokio::fs::File::open(&kv.path).await?;
+        let filename = std::path::Path::new(&kv.path)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| kv.path.clone());
+        let part = reqwest::multipart::Part::stream(file).file_name(filename);
+        form = form.part(kv.k.clone(), part);
+    }
     for pair in args.body.iter() {
-        body.insert(&pair.k, &pair.v);
+        form = form.text(pair.k.clone(), pair.v.to_form_value());
     }
-    let resp = client.post(&args.url).json(&body).send().await?;
-    print_resp(resp).await
+    let ctx = SessionContext::load(opts)?;
+    let builder = client.request(method, &args.url).multipart(form);
+    let (builder, auth) = apply_auth_and_headers(builder, &args.header, opts, ctx.as_ref())?;
+    let resp = builder.send().await?;
+    print_resp(resp, ctx.map(|ctx| (ctx, auth)), opts).await
 }
 
 /// 将服务器返回的 content-type 解析成 Mime 类型
@@ -122,16 +439,68 @@ fn get_content_type(resp: &Response) -> Option<Mime> {
         .map(|v| v.to_str().unwrap().parse().unwrap())
 }
 
-/// 打印整个响应
-async fn print_resp(resp: Response) -> Result<()> {
+/// 打印整个响应。如果带了 --session，顺便把响应里的 Set-Cookie 和本次用到
+/// 的 auth 写回 session 文件，供下次使用同一个 --session 时复用。如果带了
+/// --download，body 会被流式写入文件而不是加载进内存、也不做语法高亮
+async fn print_resp(
+    resp: Response,
+    session: Option<(SessionContext, Option<SessionAuth>)>,
+    opts: &Opts,
+) -> Result<()> {
     print_status(&resp);
     print_headers(&resp);
+    if let Some((mut ctx, auth)) = session {
+        if let Some(auth) = auth {
+            ctx.session.auth = Some(auth);
+        }
+        for value in resp.headers().get_all(header::SET_COOKIE) {
+            if let Ok(s) = value.to_str() {
+                if let Some((k, v)) = s.split(';').next().and_then(|kv| kv.split_once('=')) {
+                    ctx.session
+                        .cookies
+                        .insert(k.trim().to_string(), v.trim().to_string());
+                }
+            }
+        }
+        save_session(&ctx.name, &ctx.session)?;
+    }
+    if opts.download {
+        return download_body(resp, opts).await;
+    }
     let mime = get_content_type(&resp);
     let body = resp.text().await?;
     print_body(mime, &body);
     Ok(())
 }
 
+/// `--download`：流式把响应体写入 -o/--output 指定的文件，期间根据
+/// Content-Length（如果有）打印下载进度，避免大文件把内存撑爆
+async fn download_body(resp: Response, opts: &Opts) -> Result<()> {
+    // main() 已经在发请求之前校验过 --download 必须带 -o/--output
+    let path = opts.output.as_ref().expect("--download requires --output");
+    let total = resp.content_length();
+    let mut file = tokio::fs::File::create(path).await?;
+    let mut stream = resp.bytes_stream();
+    let mut downloaded: u64 = 0;
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk?;
+        file.write_all(&chunk).await?;
+        downloaded += chunk.len() as u64;
+        match total {
+            Some(total) => print!(
+                "\r{} {:.1}%",
+                "Downloading".green(),
+                downloaded as f64 / total as f64 * 100.0
+            ),
+            None => print!("\r{} {} bytes", "Downloading".green(), downloaded),
+        }
+        std::io::stdout().flush()?;
+    }
+    println!();
+    println!("Saved to {}", path.display());
+    Ok(())
+}
+
 // 打印服务器版本号 + 状态码
 fn print_status(resp: &Response) {
     let status = format!("{:?} {}", resp.version(), resp.status()).blue();
@@ -181,16 +550,34 @@ fn print_syntect(s: &str, ext: &str) {
 #[tokio::main]
 async fn main() -> Result<()> {
     let opts: Opts = Opts::parse();
+    if opts.download && opts.output.is_none() {
+        return Err(anyhow!("--download requires -o/--output <path>"));
+    }
     let mut headers = header::HeaderMap::new();
     // 为我们的 http 客户端添加一些缺省的 HTTP 头
     headers.insert("X-POWERED-BY", "Rust".parse()?);
     headers.insert(header::USER_AGENT, "Rust Httpie".parse()?);
-    let client = reqwest::Client::builder()
-        .default_headers(headers)
-        .build()?;
+    let mut builder = reqwest::Client::builder().default_headers(headers);
+    if opts.no_proxy {
+        builder = builder.no_proxy();
+    } else if let Some(proxy) = &opts.proxy {
+        builder = builder.proxy(reqwest::Proxy::all(proxy)?);
+    }
+    // 否则交给 reqwest 按 HTTPS_PROXY/no_proxy 等环境变量自动处理
+    let redirect_policy = if opts.no_follow {
+        reqwest::redirect::Policy::none()
+    } else {
+        reqwest::redirect::Policy::limited(opts.max_redirects)
+    };
+    let client = builder.redirect(redirect_policy).build()?;
     match opts.subcmd {
-        SubCommand::Get(ref args) => get(client, args).await?,
-        SubCommand::Post(ref args) => post(client, args).await?,
+        SubCommand::Get(ref args) => get(client, args, &opts).await?,
+        SubCommand::Post(ref args) => post(client, args, &opts).await?,
+        SubCommand::Put(ref args) => mutate(Method::PUT, client, args, &opts).await?,
+        SubCommand::Patch(ref args) => mutate(Method::PATCH, client, args, &opts).await?,
+        SubCommand::Delete(ref args) => mutate(Method::DELETE, client, args, &opts).await?,
+        SubCommand::Head(ref args) => fetch(Method::HEAD, client, args, &opts).await?,
+        SubCommand::Options(ref args) => fetch(Method::OPTIONS, client, args, &opts).await?,
     };
 
     Ok(())
@@ -226,4 +613,63 @@ mod tests {
             }
         );
     }
+
+    #[test]
+    fn parse_body_kv_works() {
+        assert!(parse_body_kv("a").is_err());
+        assert_eq!(
+            parse_body_kv("name=foo").unwrap(),
+            BodyKv {
+                k: "name".into(),
+                v: JsonValue::Str("foo".into())
+            }
+        );
+
+        assert_eq!(
+            parse_body_kv("count:=42").unwrap(),
+            BodyKv {
+                k: "count".into(),
+                v: JsonValue::Raw(serde_json::json!(42))
+            }
+        );
+
+        assert_eq!(
+            parse_body_kv("active:=true").unwrap(),
+            BodyKv {
+                k: "active".into(),
+                v: JsonValue::Raw(serde_json::json!(true))
+            }
+        );
+
+        assert_eq!(
+            parse_body_kv("tags:=[1,2]").unwrap(),
+            BodyKv {
+                k: "tags".into(),
+                v: JsonValue::Raw(serde_json::json!([1, 2]))
+            }
+        );
+
+        assert!(parse_body_kv("count:=not-json").is_err());
+    }
+
+    #[test]
+    fn parse_file_kv_works() {
+        assert!(parse_file_kv("avatar").is_err());
+        assert_eq!(
+            parse_file_kv("avatar@/tmp/photo.png").unwrap(),
+            FileKv {
+                k: "avatar".into(),
+                path: "/tmp/photo.png".into()
+            }
+        );
+
+        // 路径本身可以包含 '@'，splitn(2, ..) 保证只在第一个 '@' 处切分
+        assert_eq!(
+            parse_file_kv("avatar@/tmp/user@host/photo.png").unwrap(),
+            FileKv {
+                k: "avatar".into(),
+                path: "/tmp/user@host/photo.png".into()
+            }
+        );
+    }
 }